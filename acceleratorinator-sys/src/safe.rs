@@ -0,0 +1,856 @@
+//! Safe wrappers around the raw bindings
+
+use crate::CringUsbConnection;
+use core::{ffi::c_int, fmt::Display, ptr::null_mut};
+
+/// An open USB connection.
+///
+/// The underlying USB structure is freed when this is dropped.
+pub struct Connection {
+    raw: *mut CringUsbConnection,
+    vendor_id: u16,
+    product_id: u16,
+}
+
+impl Connection {
+    /// Create the USB structure and connect it to the first interface of the first device with the given ids
+    pub fn connect(vendor_id: u16, product_id: u16) -> Result<Self, CringError> {
+        let mut raw = null_mut();
+        wrap(unsafe { crate::cring_usb_create(&mut raw) })?;
+
+        // Constructed before connecting so the structure is freed if connecting fails
+        let connection = Self {
+            raw,
+            vendor_id,
+            product_id,
+        };
+        wrap(unsafe { crate::cring_usb_connect(connection.raw, vendor_id, product_id) })?;
+
+        Ok(connection)
+    }
+
+    /// Connect to the first acceleratorinator that can be found
+    pub fn connect_acceleratorinator() -> Result<Self, CringError> {
+        Self::connect(crate::CRING_ACC_VID as u16, crate::CRING_ACC_PID as u16)
+    }
+
+    /// Send a bulk out message. The endpoint must *not* have its top-bit (`0x80`) set.
+    ///
+    /// Returns the amount of bytes that were sent.
+    pub fn bulk_out(&mut self, ep: u8, data: &[u8]) -> Result<usize, CringError> {
+        wrap(unsafe { crate::cring_usb_bulk_out(self.raw, ep, data.as_ptr(), data.len()) })
+            .map(|len| len as usize)
+    }
+
+    /// Send a bulk in message. The endpoint must have its top-bit (`0x80`) set.
+    ///
+    /// Returns the amount of bytes that were received into `data`.
+    pub fn bulk_in(&mut self, ep: u8, data: &mut [u8]) -> Result<usize, CringError> {
+        wrap(unsafe { crate::cring_usb_bulk_in(self.raw, ep, data.as_mut_ptr(), data.len()) })
+            .map(|len| len as usize)
+    }
+
+    /// Send the bmp image to the acceleratorinator.
+    /// The processed image returned by the device is written back into `bmp`.
+    pub fn send_bmp(&mut self, bmp: &mut [u8]) -> Result<(), CringError> {
+        wrap(unsafe { crate::cring_acc_send_bmp(self.raw, bmp.as_mut_ptr(), bmp.len()) })?;
+        Ok(())
+    }
+
+    /// Free the USB structure and connect again to the device ids this connection was made with
+    pub fn reconnect(&mut self) -> Result<(), CringError> {
+        wrap(unsafe { crate::cring_usb_free(&mut self.raw) })?;
+        wrap(unsafe { crate::cring_usb_create(&mut self.raw) })?;
+        wrap(unsafe { crate::cring_usb_connect(self.raw, self.vendor_id, self.product_id) })?;
+        Ok(())
+    }
+
+    /// Like [Self::send_bmp], but on a USB error the connection is re-established and the image is sent again.
+    ///
+    /// The library only writes into `bmp` once the transfer has fully succeeded,
+    /// so a failed attempt leaves the original image in place for the next one.
+    ///
+    /// Reconnecting only recreates the host side handle, the device is not reset. If the error happened
+    /// during the upload, the device still holds the partial image and appends the resent one to it.
+    /// That fails with [CringError::AccParse] at best, and returns a garbage image as a success at worst.
+    #[cfg(feature = "std")]
+    pub fn send_bmp_with_retry(
+        &mut self,
+        bmp: &mut [u8],
+        policy: &RetryPolicy,
+    ) -> Result<(), CringError> {
+        retry(
+            self,
+            policy,
+            |connection| connection.send_bmp(bmp),
+            Self::reconnect,
+            std::thread::sleep,
+        )
+    }
+
+    /// Send a raw framebuffer to the acceleratorinator, without having to build a bmp file for it.
+    /// The processed pixels returned by the device are written back into `pixels`.
+    ///
+    /// The pixels are rows of `width` pixels from top to bottom without any padding.
+    /// With a `bpp` of 24 every pixel is RGB888 (`B, G, R`) and with 32 it's XRGB8888 (`B, G, R, X`).
+    #[cfg(feature = "std")]
+    pub fn send_raw(
+        &mut self,
+        pixels: &mut [u8],
+        width: u32,
+        height: u32,
+        bpp: u16,
+    ) -> Result<(), CringError> {
+        let mut bmp = raw_to_bmp(pixels, width, height, bpp)?;
+        self.send_bmp(&mut bmp)?;
+
+        // Rows in the bmp are bottom to top and padded to 4 bytes
+        let row_len = pixels.len() / height as usize;
+        let bmp_rows = bmp[RAW_BMP_HEADER_LEN..].chunks_exact(row_len.next_multiple_of(4));
+        for (row, bmp_row) in pixels.chunks_exact_mut(row_len).rev().zip(bmp_rows) {
+            row.copy_from_slice(&bmp_row[..row_len]);
+        }
+
+        Ok(())
+    }
+
+    /// Read the bmp file at `in_path`, send it to the acceleratorinator and write the processed image to `out_path`
+    #[cfg(feature = "std")]
+    pub fn process_file(
+        &mut self,
+        in_path: impl AsRef<std::path::Path>,
+        out_path: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<()> {
+        let mut bmp = std::fs::read(in_path)?;
+        self.send_bmp(&mut bmp).map_err(std::io::Error::other)?;
+        std::fs::write(out_path, bmp)
+    }
+
+    /// Get a [std::io::Write] implementation that sends bulk out messages to the endpoint
+    #[cfg(feature = "std")]
+    pub fn bulk_writer(&mut self, ep: u8) -> BulkWriter<'_> {
+        BulkWriter {
+            connection: self,
+            ep,
+        }
+    }
+
+    /// Get a [std::io::Read] implementation that receives bulk in messages from the endpoint
+    #[cfg(feature = "std")]
+    pub fn bulk_reader(&mut self, ep: u8) -> BulkReader<'_> {
+        BulkReader {
+            connection: self,
+            ep,
+            buffer: PacketBuffer::new(),
+        }
+    }
+
+    /// Free the USB structure, reporting any error that dropping the connection would ignore
+    pub fn close(mut self) -> Result<(), CringError> {
+        let result = wrap(unsafe { crate::cring_usb_free(&mut self.raw) });
+        // Already freed (or failed to be), so skip the drop
+        core::mem::forget(self);
+        result.map(|_| ())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe {
+            crate::cring_usb_free(&mut self.raw);
+        }
+    }
+}
+
+/// Writes every buffer as a separate bulk out message.
+///
+/// Writing an empty buffer does nothing. To send a zero length packet, use [Connection::bulk_out] directly.
+#[cfg(feature = "std")]
+pub struct BulkWriter<'a> {
+    connection: &'a mut Connection,
+    ep: u8,
+}
+
+#[cfg(feature = "std")]
+impl std::io::Write for BulkWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let (connection, ep) = (&mut *self.connection, self.ep);
+        bulk_write(buf, |data| connection.bulk_out(ep, data))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads by receiving bulk in messages.
+///
+/// The device always sends full packets, which overflow a buffer that is smaller than a packet.
+/// So messages are received into an internal buffer, which serves the reads of any size.
+///
+/// A zero length packet from the device is reported as a read of 0 bytes, which [std::io::Read] treats as the end of the stream.
+#[cfg(feature = "std")]
+pub struct BulkReader<'a> {
+    connection: &'a mut Connection,
+    ep: u8,
+    buffer: PacketBuffer,
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for BulkReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let (connection, ep) = (&mut *self.connection, self.ep);
+        self.buffer.read(buf, |data| connection.bulk_in(ep, data))
+    }
+}
+
+/// The max packet size of the bulk endpoints
+#[cfg(feature = "std")]
+const MAX_PACKET_SIZE: usize = 64;
+
+/// The [std::io::Write] logic of [BulkWriter], with the bulk out call passed in so it can be tested
+#[cfg(feature = "std")]
+fn bulk_write(
+    buf: &[u8],
+    bulk_out: impl FnOnce(&[u8]) -> Result<usize, CringError>,
+) -> std::io::Result<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+
+    bulk_out(buf).map_err(std::io::Error::other)
+}
+
+/// Received bulk in data that hasn't been read yet
+#[cfg(feature = "std")]
+struct PacketBuffer {
+    /// A multiple of [MAX_PACKET_SIZE] long
+    data: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+#[cfg(feature = "std")]
+impl PacketBuffer {
+    fn new() -> Self {
+        Self {
+            data: vec![0; MAX_PACKET_SIZE * 64],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Read buffered data into `buf`, receiving more with `bulk_in` when the buffer is empty
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+        bulk_in: impl FnOnce(&mut [u8]) -> Result<usize, CringError>,
+    ) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.start == self.end {
+            // Reads that can take whole packets don't need to go through the buffer
+            if buf.len() >= self.data.len() {
+                let len = buf.len() / MAX_PACKET_SIZE * MAX_PACKET_SIZE;
+                return bulk_in(&mut buf[..len]).map_err(std::io::Error::other);
+            }
+
+            self.start = 0;
+            self.end = bulk_in(&mut self.data).map_err(std::io::Error::other)?;
+        }
+
+        let len = buf.len().min(self.end - self.start);
+        buf[..len].copy_from_slice(&self.data[self.start..][..len]);
+        self.start += len;
+
+        Ok(len)
+    }
+}
+
+/// How [Connection::send_bmp_with_retry] recovers from USB errors
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The total amount of attempts, including the first one.
+    ///
+    /// Every reconnect also uses up an attempt, so if reconnecting fails the image is sent fewer times than this.
+    pub max_attempts: u32,
+    /// The time to wait before the first reconnect. Doubles with every next attempt.
+    pub backoff: std::time::Duration,
+}
+
+/// The attempt counting of [Connection::send_bmp_with_retry], with the steps passed in so it can be tested
+#[cfg(feature = "std")]
+fn retry<T>(
+    target: &mut T,
+    policy: &RetryPolicy,
+    mut send: impl FnMut(&mut T) -> Result<(), CringError>,
+    mut reconnect: impl FnMut(&mut T) -> Result<(), CringError>,
+    mut sleep: impl FnMut(std::time::Duration),
+) -> Result<(), CringError> {
+    let mut attempt = 1;
+    let mut backoff = policy.backoff;
+
+    loop {
+        match send(target) {
+            Err(CringError::Usb) if attempt < policy.max_attempts => {}
+            result => return result,
+        }
+
+        // The device may still be re-enumerating, so keep trying to reconnect with the same attempt budget
+        loop {
+            sleep(backoff);
+            backoff = backoff.saturating_mul(2);
+            attempt += 1;
+
+            match reconnect(target) {
+                Ok(()) => break,
+                Err(e) if attempt >= policy.max_attempts => return Err(e),
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+/// A host side transform of the image, e.g. scaling it down before it's sent
+#[cfg(feature = "std")]
+pub type Stage<E> = Box<dyn FnMut(&mut Vec<u8>) -> Result<(), E>>;
+
+/// Transform stages that are applied to the image around the device round trip.
+///
+/// The stages can fail with any error that a [CringError] can be converted into.
+#[cfg(feature = "std")]
+pub struct Pipeline<E = CringError> {
+    pre: Vec<Stage<E>>,
+    post: Vec<Stage<E>>,
+}
+
+#[cfg(feature = "std")]
+impl<E: From<CringError>> Pipeline<E> {
+    pub fn new() -> Self {
+        Self {
+            pre: Vec::new(),
+            post: Vec::new(),
+        }
+    }
+
+    /// Add a stage that runs on the image before it's sent, after the stages that were added before
+    pub fn pre(mut self, stage: impl FnMut(&mut Vec<u8>) -> Result<(), E> + 'static) -> Self {
+        self.pre.push(Box::new(stage));
+        self
+    }
+
+    /// Add a stage that runs on the image the device returned, after the stages that were added before
+    pub fn post(mut self, stage: impl FnMut(&mut Vec<u8>) -> Result<(), E> + 'static) -> Self {
+        self.post.push(Box::new(stage));
+        self
+    }
+
+    /// Run the pre stages on `bmp`, send it with [Connection::send_bmp] and run the post stages on the result.
+    ///
+    /// Stops at the first error, so the post stages only see a successfully processed image.
+    pub fn send(&mut self, connection: &mut Connection, bmp: &mut Vec<u8>) -> Result<(), E> {
+        self.run(bmp, |bmp| connection.send_bmp(bmp))
+    }
+
+    fn run(
+        &mut self,
+        bmp: &mut Vec<u8>,
+        round_trip: impl FnOnce(&mut [u8]) -> Result<(), CringError>,
+    ) -> Result<(), E> {
+        for stage in &mut self.pre {
+            stage(bmp)?;
+        }
+
+        round_trip(bmp)?;
+
+        for stage in &mut self.post {
+            stage(bmp)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: From<CringError>> Default for Pipeline<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The length of the file header plus the 40 byte info header
+#[cfg(feature = "std")]
+const RAW_BMP_HEADER_LEN: usize = 14 + 40;
+
+/// Build an uncompressed bottom-up bmp file around the pixels of [Connection::send_raw]
+#[cfg(feature = "std")]
+fn raw_to_bmp(pixels: &[u8], width: u32, height: u32, bpp: u16) -> Result<Vec<u8>, CringError> {
+    let pixel_len = match bpp {
+        24 => 3,
+        32 => 4,
+        _ => return Err(CringError::Invalid),
+    };
+
+    let row_len = (width as usize)
+        .checked_mul(pixel_len)
+        .ok_or(CringError::Invalid)?;
+    if row_len == 0 || height == 0 || Some(pixels.len()) != row_len.checked_mul(height as usize) {
+        return Err(CringError::Invalid);
+    }
+
+    let padded_row_len = row_len.next_multiple_of(4);
+    let image_data_len = padded_row_len
+        .checked_mul(height as usize)
+        .and_then(|len| u32::try_from(len).ok())
+        .ok_or(CringError::Invalid)?;
+    let file_size = image_data_len
+        .checked_add(RAW_BMP_HEADER_LEN as u32)
+        .ok_or(CringError::Invalid)?;
+    let width = i32::try_from(width).map_err(|_| CringError::Invalid)?;
+    let height = i32::try_from(height).map_err(|_| CringError::Invalid)?;
+
+    let mut bmp = Vec::with_capacity(file_size as usize);
+
+    // File header
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&file_size.to_le_bytes());
+    bmp.extend_from_slice(&[0; 4]);
+    bmp.extend_from_slice(&(RAW_BMP_HEADER_LEN as u32).to_le_bytes());
+
+    // Info header, uncompressed without a color table
+    bmp.extend_from_slice(&40u32.to_le_bytes());
+    bmp.extend_from_slice(&width.to_le_bytes());
+    bmp.extend_from_slice(&height.to_le_bytes());
+    bmp.extend_from_slice(&1u16.to_le_bytes());
+    bmp.extend_from_slice(&bpp.to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&image_data_len.to_le_bytes());
+    bmp.extend_from_slice(&[0; 16]);
+
+    for row in pixels.chunks_exact(row_len).rev() {
+        bmp.extend_from_slice(row);
+        bmp.resize(bmp.len() + padded_row_len - row_len, 0);
+    }
+
+    Ok(bmp)
+}
+
+fn wrap(val: c_int) -> Result<u32, CringError> {
+    match CringError::try_from(val) {
+        Ok(error) => Err(error),
+        Err(val) => Ok(val as u32),
+    }
+}
+
+/// The error codes returned by the library
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CringError {
+    /// Operation has already happened so this call in invalid
+    Already,
+    /// Some parameter is invalid
+    Invalid,
+    /// The search yielded no valid result
+    NotPresent,
+    /// There was an error interacting with the USB
+    Usb,
+    /// Unknown acceleratorinator error
+    AccUnknown,
+    /// Unsupported compression
+    AccUnsupportedCompression,
+    /// Parse failure
+    AccParse,
+    /// A code these bindings don't know about
+    Other(UnknownCode),
+}
+
+/// A negative code that none of the other [CringError] variants stand for.
+///
+/// Can only be made by converting the code into a [CringError], so every known code has a single representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownCode(c_int);
+
+impl UnknownCode {
+    pub fn code(self) -> c_int {
+        self.0
+    }
+}
+
+/// Fails with the value itself when it isn't negative, since those are success codes
+impl TryFrom<c_int> for CringError {
+    type Error = c_int;
+
+    fn try_from(value: c_int) -> Result<Self, Self::Error> {
+        Ok(match value {
+            crate::CRING_EALREADY => Self::Already,
+            crate::CRING_EINVAL => Self::Invalid,
+            crate::CRING_ENOTPRESENT => Self::NotPresent,
+            crate::CRING_EUSB => Self::Usb,
+            crate::CRING_EACC_UNKNOWN => Self::AccUnknown,
+            crate::CRING_EACC_UNSUP_COMP => Self::AccUnsupportedCompression,
+            crate::CRING_EACC_PARSE => Self::AccParse,
+            value if value < 0 => Self::Other(UnknownCode(value)),
+            value => return Err(value),
+        })
+    }
+}
+
+impl From<CringError> for c_int {
+    fn from(value: CringError) -> Self {
+        match value {
+            CringError::Already => crate::CRING_EALREADY,
+            CringError::Invalid => crate::CRING_EINVAL,
+            CringError::NotPresent => crate::CRING_ENOTPRESENT,
+            CringError::Usb => crate::CRING_EUSB,
+            CringError::AccUnknown => crate::CRING_EACC_UNKNOWN,
+            CringError::AccUnsupportedCompression => crate::CRING_EACC_UNSUP_COMP,
+            CringError::AccParse => crate::CRING_EACC_PARSE,
+            CringError::Other(unknown) => unknown.code(),
+        }
+    }
+}
+
+impl Display for CringError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Cring error `{}` => ", c_int::from(*self))?;
+
+        match self {
+            CringError::Already => write!(f, "ALREADY"),
+            CringError::Invalid => write!(f, "INVAL"),
+            CringError::NotPresent => write!(f, "NOTPRESENT"),
+            CringError::Usb => write!(f, "USB"),
+
+            CringError::AccUnknown => write!(f, "ACC_UNKNOWN"),
+            CringError::AccUnsupportedCompression => write!(f, "ACC_UNSUP_COMP"),
+            CringError::AccParse => write!(f, "ACC_PARSE"),
+            CringError::Other(_) => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
+impl core::error::Error for CringError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOWN_ERRORS: [(c_int, CringError); 7] = [
+        (crate::CRING_EALREADY, CringError::Already),
+        (crate::CRING_EINVAL, CringError::Invalid),
+        (crate::CRING_ENOTPRESENT, CringError::NotPresent),
+        (crate::CRING_EUSB, CringError::Usb),
+        (crate::CRING_EACC_UNKNOWN, CringError::AccUnknown),
+        (
+            crate::CRING_EACC_UNSUP_COMP,
+            CringError::AccUnsupportedCompression,
+        ),
+        (crate::CRING_EACC_PARSE, CringError::AccParse),
+    ];
+
+    #[test]
+    fn known_error_codes_round_trip() {
+        for (code, error) in KNOWN_ERRORS {
+            assert_eq!(CringError::try_from(code), Ok(error));
+            assert_eq!(c_int::from(error), code);
+        }
+    }
+
+    #[test]
+    fn unknown_error_codes_round_trip() {
+        for code in [-5, -99, -103, c_int::MIN] {
+            let error = CringError::try_from(code).unwrap();
+
+            assert_eq!(error, CringError::Other(UnknownCode(code)));
+            assert_eq!(c_int::from(error), code);
+        }
+    }
+
+    #[test]
+    fn success_codes_are_not_errors() {
+        for code in [0, 1, c_int::MAX] {
+            assert_eq!(CringError::try_from(code), Err(code));
+            assert_eq!(wrap(code), Ok(code as u32));
+        }
+
+        assert_eq!(wrap(crate::CRING_EUSB), Err(CringError::Usb));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn raw_to_bmp_layout() {
+        // 2x2 pixels, top row red, bottom row blue
+        let pixels = [0, 0, 255, 0, 0, 255, 255, 0, 0, 255, 0, 0];
+        let bmp = raw_to_bmp(&pixels, 2, 2, 24).unwrap();
+
+        assert_eq!(bmp.len(), RAW_BMP_HEADER_LEN + 2 * 8);
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(bmp[2..6], (bmp.len() as u32).to_le_bytes());
+        assert_eq!(bmp[10..14], 54u32.to_le_bytes());
+        assert_eq!(bmp[18..22], 2i32.to_le_bytes());
+        assert_eq!(bmp[22..26], 2i32.to_le_bytes());
+        assert_eq!(bmp[28..30], 24u16.to_le_bytes());
+        assert_eq!(bmp[34..38], 16u32.to_le_bytes());
+
+        // Bottom row first, each padded from 6 to 8 bytes
+        assert_eq!(
+            bmp[RAW_BMP_HEADER_LEN..],
+            [255, 0, 0, 255, 0, 0, 0, 0, 0, 0, 255, 0, 0, 255, 0, 0]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn raw_to_bmp_rejects_invalid_arguments() {
+        let pixels = [0; 12];
+
+        assert_eq!(raw_to_bmp(&pixels, 2, 2, 16), Err(CringError::Invalid));
+        assert_eq!(raw_to_bmp(&pixels, 0, 2, 24), Err(CringError::Invalid));
+        assert_eq!(raw_to_bmp(&pixels, 2, 0, 24), Err(CringError::Invalid));
+        assert_eq!(raw_to_bmp(&pixels, 2, 1, 32), Err(CringError::Invalid));
+        assert!(raw_to_bmp(&pixels, 3, 1, 32).is_ok());
+        assert_eq!(
+            raw_to_bmp(&pixels, u32::MAX, u32::MAX, 32),
+            Err(CringError::Invalid)
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn pipeline_runs_stages_in_order() {
+        let mut pipeline: Pipeline = Pipeline::new()
+            .pre(|bmp: &mut Vec<u8>| {
+                bmp.push(1);
+                Ok(())
+            })
+            .pre(|bmp: &mut Vec<u8>| {
+                bmp.push(2);
+                Ok(())
+            })
+            .post(|bmp: &mut Vec<u8>| {
+                bmp.push(4);
+                Ok(())
+            });
+
+        let mut bmp = vec![0];
+        let result = pipeline.run(&mut bmp, |bmp| {
+            assert_eq!(bmp, [0, 1, 2]);
+            bmp[0] = 3;
+            Ok(())
+        });
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(bmp, [3, 1, 2, 4]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn pipeline_skips_post_stages_on_error() {
+        let mut pipeline: Pipeline = Pipeline::new().post(|bmp: &mut Vec<u8>| {
+            bmp.push(1);
+            Ok(())
+        });
+
+        let mut bmp = vec![0];
+        let result = pipeline.run(&mut bmp, |_| Err(CringError::Usb));
+
+        assert_eq!(result, Err(CringError::Usb));
+        assert_eq!(bmp, [0]);
+    }
+
+    /// Records the steps [retry] takes, failing them with the queued results
+    #[cfg(feature = "std")]
+    #[derive(Default)]
+    struct MockDevice {
+        sends: Vec<Result<(), CringError>>,
+        reconnects: Vec<Result<(), CringError>>,
+        steps: Vec<&'static str>,
+        sleeps: Vec<u64>,
+    }
+
+    #[cfg(feature = "std")]
+    fn retry_mock(device: &mut MockDevice, max_attempts: u32) -> Result<(), CringError> {
+        let policy = RetryPolicy {
+            max_attempts,
+            backoff: std::time::Duration::from_millis(100),
+        };
+
+        let mut sleeps = Vec::new();
+        let result = retry(
+            device,
+            &policy,
+            |device| {
+                device.steps.push("send");
+                device.sends.remove(0)
+            },
+            |device| {
+                device.steps.push("reconnect");
+                device.reconnects.remove(0)
+            },
+            |backoff| sleeps.push(backoff.as_millis() as u64),
+        );
+        device.sleeps = sleeps;
+
+        result
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn retry_resends_after_usb_errors() {
+        let mut device = MockDevice {
+            sends: vec![Err(CringError::Usb), Err(CringError::Usb), Ok(())],
+            reconnects: vec![Ok(()), Ok(())],
+            ..Default::default()
+        };
+
+        assert_eq!(retry_mock(&mut device, 3), Ok(()));
+        assert_eq!(
+            device.steps,
+            ["send", "reconnect", "send", "reconnect", "send"]
+        );
+        assert_eq!(device.sleeps, [100, 200]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn retry_gives_up_after_max_attempts() {
+        let mut device = MockDevice {
+            sends: vec![Err(CringError::Usb), Err(CringError::Usb)],
+            reconnects: vec![Ok(())],
+            ..Default::default()
+        };
+
+        assert_eq!(retry_mock(&mut device, 2), Err(CringError::Usb));
+        assert_eq!(device.steps, ["send", "reconnect", "send"]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn retry_counts_failed_reconnects_as_attempts() {
+        let mut device = MockDevice {
+            sends: vec![Err(CringError::Usb), Ok(())],
+            reconnects: vec![Err(CringError::NotPresent), Ok(())],
+            ..Default::default()
+        };
+        assert_eq!(retry_mock(&mut device, 3), Ok(()));
+        assert_eq!(device.steps, ["send", "reconnect", "reconnect", "send"]);
+        assert_eq!(device.sleeps, [100, 200]);
+
+        let mut device = MockDevice {
+            sends: vec![Err(CringError::Usb)],
+            reconnects: vec![Err(CringError::NotPresent)],
+            ..Default::default()
+        };
+        assert_eq!(retry_mock(&mut device, 2), Err(CringError::NotPresent));
+        assert_eq!(device.steps, ["send", "reconnect"]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn retry_only_resends_on_usb_errors() {
+        let mut device = MockDevice {
+            sends: vec![Err(CringError::AccParse)],
+            ..Default::default()
+        };
+
+        assert_eq!(retry_mock(&mut device, 5), Err(CringError::AccParse));
+        assert_eq!(device.steps, ["send"]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn retry_without_attempts_still_sends_once() {
+        let mut device = MockDevice {
+            sends: vec![Err(CringError::Usb)],
+            ..Default::default()
+        };
+
+        assert_eq!(retry_mock(&mut device, 0), Err(CringError::Usb));
+        assert_eq!(device.steps, ["send"]);
+    }
+
+    /// Sends the packets like the device does, failing reads into buffers smaller than a packet
+    #[cfg(feature = "std")]
+    struct MockEndpoint {
+        data: Vec<u8>,
+        buffer: PacketBuffer,
+        reads: Vec<usize>,
+    }
+
+    #[cfg(feature = "std")]
+    impl std::io::Read for MockEndpoint {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let (data, reads) = (&mut self.data, &mut self.reads);
+            self.buffer.read(buf, |buf| {
+                reads.push(buf.len());
+
+                let len = data.len().min(MAX_PACKET_SIZE);
+                if buf.len() < len {
+                    return Err(CringError::Usb);
+                }
+
+                buf[..len].copy_from_slice(&data[..len]);
+                data.drain(..len);
+                Ok(len)
+            })
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn mock_endpoint(len: usize) -> MockEndpoint {
+        MockEndpoint {
+            data: (0..len).map(|i| i as u8).collect(),
+            buffer: PacketBuffer::new(),
+            reads: Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn bulk_reader_serves_small_reads() {
+        use std::io::Read;
+
+        let mut endpoint = mock_endpoint(200);
+        let mut data = Vec::new();
+        endpoint.read_to_end(&mut data).unwrap();
+
+        assert_eq!(data, (0..200).map(|i| i as u8).collect::<Vec<_>>());
+        assert!(endpoint.reads.iter().all(|len| len % MAX_PACKET_SIZE == 0));
+
+        let mut endpoint = mock_endpoint(100);
+        let mut byte = [0];
+        endpoint.read_exact(&mut byte).unwrap();
+        endpoint.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, [1]);
+        assert_eq!(endpoint.reads.len(), 1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn bulk_reader_reads_large_buffers_directly() {
+        use std::io::Read;
+
+        let mut endpoint = mock_endpoint(10);
+        let mut data = vec![0; MAX_PACKET_SIZE * 64 + 10];
+
+        assert_eq!(endpoint.read(&mut data).unwrap(), 10);
+        assert_eq!(endpoint.reads, [MAX_PACKET_SIZE * 64]);
+
+        // The zero length packet at the end of the transfer
+        assert_eq!(endpoint.read(&mut data).unwrap(), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn bulk_write_skips_empty_writes() {
+        assert_eq!(bulk_write(&[], |_| panic!("nothing to send")).unwrap(), 0);
+        assert_eq!(bulk_write(&[1, 2, 3], |data| Ok(data.len())).unwrap(), 3);
+
+        let error = bulk_write(&[1], |_| Err(CringError::Usb)).unwrap_err();
+        assert_eq!(
+            error.into_inner().unwrap().downcast_ref::<CringError>(),
+            Some(&CringError::Usb)
+        );
+    }
+}
@@ -16,7 +16,7 @@ fn main() {
     }
 
     let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
-    let target_dir = out_dir.ancestors().skip(3).next().unwrap();
+    let target_dir = out_dir.ancestors().nth(3).unwrap();
 
     std::fs::copy(
         "../libacceleratorinator.so",
@@ -0,0 +1,322 @@
+//! Host side checks of bmp files, so files the acceleratorinator can't handle are rejected before the USB round trip
+
+use std::{error::Error, fmt::Display};
+use tinybmp::{CompressionMethod, Header, ParseError, RawBmp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidBmp {
+    /// The file couldn't be parsed as a bmp
+    Parse(ParseError),
+    /// The file is valid, but uses a compression method the device doesn't support
+    UnsupportedCompression(CompressionMethod),
+    /// The file is shorter than the pixel data described by the header
+    Truncated { expected: usize, available: usize },
+}
+
+impl Display for InvalidBmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid bmp file: ")?;
+
+        match self {
+            InvalidBmp::Parse(ParseError::UnsupportedBpp(bpp)) => {
+                write!(f, "unsupported bit depth of {bpp} bits per pixel")
+            }
+            InvalidBmp::Parse(ParseError::UnexpectedEndOfFile) => {
+                write!(
+                    f,
+                    "the file ends before the header or image data is complete"
+                )
+            }
+            InvalidBmp::Parse(ParseError::InvalidFileSignature(signature)) => {
+                write!(f, "invalid file signature {signature:02x?}, expected `BM`")
+            }
+            InvalidBmp::Parse(ParseError::UnsupportedCompressionMethod(method)) => {
+                write!(f, "unknown compression method {method}")
+            }
+            InvalidBmp::Parse(ParseError::UnsupportedHeaderLength(len)) => {
+                write!(f, "unsupported DIB header length of {len} bytes")
+            }
+            InvalidBmp::Parse(ParseError::UnsupportedChannelMasks) => {
+                write!(f, "unsupported channel masks")
+            }
+            InvalidBmp::Parse(ParseError::InvalidImageDimensions) => {
+                write!(f, "invalid image dimensions")
+            }
+            InvalidBmp::UnsupportedCompression(method) => {
+                write!(f, "{method:?} compressed images are not supported")
+            }
+            InvalidBmp::Truncated {
+                expected,
+                available,
+            } => write!(
+                f,
+                "the header describes {expected} bytes of pixel data, but the file only contains {available}"
+            ),
+        }
+    }
+}
+
+impl Error for InvalidBmp {}
+
+/// Check that the bmp file is one the acceleratorinator can process
+pub fn validate(bmp: &[u8]) -> Result<(), InvalidBmp> {
+    let raw_bmp = RawBmp::from_slice(bmp).map_err(InvalidBmp::Parse)?;
+
+    let header = raw_bmp.header();
+
+    match header.compression_method {
+        CompressionMethod::Rgb | CompressionMethod::Bitfields => {}
+        method @ (CompressionMethod::Rle8 | CompressionMethod::Rle4) => {
+            return Err(InvalidBmp::UnsupportedCompression(method))
+        }
+    }
+
+    // The image data length in the header is allowed to be 0 for uncompressed images,
+    // so it can't be relied on to catch truncated files
+    let expected = row_len(header) * header.image_size.height as usize;
+    let available = bmp.len().saturating_sub(header.image_data_start);
+
+    if available < expected {
+        return Err(InvalidBmp::Truncated {
+            expected,
+            available,
+        });
+    }
+
+    Ok(())
+}
+
+/// Invert the colors of the bmp file in place, the way the acceleratorinator is expected to.
+///
+/// The headers and the row padding are left as-is. Paletted images (1, 4 and 8 bpp) get the colors of
+/// their color table inverted, and the pixels keep pointing at the same entries. Other images only
+/// get their color channels inverted, so unused bits and the alpha channel are kept.
+pub fn invert(bmp: &mut [u8]) -> Result<(), InvalidBmp> {
+    validate(bmp)?;
+    let raw_bmp = RawBmp::from_slice(bmp).map_err(InvalidBmp::Parse)?;
+    let header = *raw_bmp.header();
+
+    if header.bpp.bits() <= 8 {
+        let entries = raw_bmp.color_table().map_or(0, |table| table.len());
+
+        // The color table directly follows the DIB header, which starts with its own length
+        let dib_header_len = u32::from_le_bytes(bmp[14..18].try_into().unwrap()) as usize;
+        let color_table_start = 14 + dib_header_len;
+
+        // Every entry is `B, G, R, reserved`
+        for entry in bmp[color_table_start..][..entries * 4].chunks_exact_mut(4) {
+            entry[..3].iter_mut().for_each(|byte| *byte ^= 0xFF);
+        }
+
+        return Ok(());
+    }
+
+    let row_len = row_len(&header);
+    let pixel_bits = header.image_size.width as usize * header.bpp.bits() as usize;
+    let rows = bmp[header.image_data_start..]
+        .chunks_exact_mut(row_len)
+        .take(header.image_size.height as usize);
+
+    // Without masks the channels are implied by the bit depth, just like tinybmp reads them:
+    // 16 bpp is Rgb555 and 32 bpp is Xrgb8888
+    let color_mask = match header.channel_masks {
+        Some(masks) => masks.red | masks.green | masks.blue,
+        None if header.bpp.bits() == 16 => 0x7FFF,
+        None => 0x00FF_FFFF,
+    };
+    let pixel_len = header.bpp.bits() as usize / 8;
+
+    for row in rows {
+        for pixel in row[..pixel_bits / 8].chunks_exact_mut(pixel_len) {
+            for (byte, mask) in pixel.iter_mut().zip(color_mask.to_le_bytes()) {
+                *byte ^= mask;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The length of a row of pixels in bytes, including the padding to a multiple of 4 bytes
+fn row_len(header: &Header) -> usize {
+    let bits = header.image_size.width as usize * header.bpp.bits() as usize;
+    bits.div_ceil(32) * 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BI_RGB: u32 = 0;
+    const BI_RLE8: u32 = 1;
+    const BI_RLE4: u32 = 2;
+    const BI_BITFIELDS: u32 = 3;
+
+    /// Build a bmp file with a 40 byte info header.
+    /// The image data length is left at 0, which is allowed for uncompressed images.
+    fn bmp(
+        width: i32,
+        height: i32,
+        bpp: u16,
+        compression: u32,
+        color_table: &[[u8; 4]],
+        pixel_data: &[u8],
+    ) -> Vec<u8> {
+        let image_data_start = 14 + 40 + color_table.len() as u32 * 4;
+        let file_size = image_data_start + pixel_data.len() as u32;
+
+        let mut bmp = Vec::new();
+        bmp.extend_from_slice(b"BM");
+        bmp.extend_from_slice(&file_size.to_le_bytes());
+        bmp.extend_from_slice(&[0; 4]);
+        bmp.extend_from_slice(&image_data_start.to_le_bytes());
+
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        bmp.extend_from_slice(&width.to_le_bytes());
+        bmp.extend_from_slice(&height.to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&bpp.to_le_bytes());
+        bmp.extend_from_slice(&compression.to_le_bytes());
+        bmp.extend_from_slice(&0u32.to_le_bytes());
+        bmp.extend_from_slice(&[0; 8]);
+        bmp.extend_from_slice(&(color_table.len() as u32).to_le_bytes());
+        bmp.extend_from_slice(&0u32.to_le_bytes());
+
+        color_table
+            .iter()
+            .for_each(|entry| bmp.extend_from_slice(entry));
+        bmp.extend_from_slice(pixel_data);
+
+        bmp
+    }
+
+    /// Build a bmp file with a 56 byte V3 header that holds the channel masks
+    fn bmp_with_masks(
+        width: i32,
+        height: i32,
+        bpp: u16,
+        masks: [u32; 4],
+        pixel_data: &[u8],
+    ) -> Vec<u8> {
+        let mut bmp = bmp(width, height, bpp, BI_BITFIELDS, &[], pixel_data);
+
+        let patch = |bmp: &mut Vec<u8>, offset: usize, extra: u32| {
+            let value = u32::from_le_bytes(bmp[offset..offset + 4].try_into().unwrap()) + extra;
+            bmp[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        patch(&mut bmp, 2, 16);
+        patch(&mut bmp, 10, 16);
+        patch(&mut bmp, 14, 16);
+
+        let masks = masks.iter().flat_map(|mask| mask.to_le_bytes());
+        bmp.splice(54..54, masks);
+
+        bmp
+    }
+
+    #[test]
+    fn validate_accepts_uncompressed() {
+        // Two rows of 1 pixel, each padded to 4 bytes
+        let bmp = bmp(1, 2, 24, BI_RGB, &[], &[1, 2, 3, 0, 4, 5, 6, 0]);
+
+        assert_eq!(validate(&bmp), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_rle() {
+        let palette = [[0; 4]; 2];
+
+        assert_eq!(
+            validate(&bmp(1, 1, 8, BI_RLE8, &palette, &[])),
+            Err(InvalidBmp::UnsupportedCompression(CompressionMethod::Rle8))
+        );
+        assert_eq!(
+            validate(&bmp(1, 1, 4, BI_RLE4, &palette, &[])),
+            Err(InvalidBmp::UnsupportedCompression(CompressionMethod::Rle4))
+        );
+    }
+
+    #[test]
+    fn validate_rejects_truncated() {
+        // Two rows of 4 bytes are needed, but only 6 bytes are there
+        let bmp = bmp(1, 2, 24, BI_RGB, &[], &[1, 2, 3, 0, 4, 5]);
+
+        assert_eq!(
+            validate(&bmp),
+            Err(InvalidBmp::Truncated {
+                expected: 8,
+                available: 6,
+            })
+        );
+    }
+
+    #[test]
+    fn validate_rejects_bad_signature() {
+        let mut bmp = bmp(1, 1, 24, BI_RGB, &[], &[1, 2, 3, 0]);
+        bmp[..2].copy_from_slice(b"MB");
+
+        assert_eq!(
+            validate(&bmp),
+            Err(InvalidBmp::Parse(ParseError::InvalidFileSignature(*b"MB")))
+        );
+    }
+
+    #[test]
+    fn invert_every_pixel_byte() {
+        let mut bmp = bmp(1, 2, 24, BI_RGB, &[], &[1, 2, 3, 0, 4, 5, 6, 0]);
+        invert(&mut bmp).unwrap();
+
+        // The padding is kept
+        assert_eq!(bmp[54..], [0xFE, 0xFD, 0xFC, 0, 0xFB, 0xFA, 0xF9, 0]);
+    }
+
+    #[test]
+    fn invert_color_table() {
+        // 3 pixels of 1 bit, the partial byte and the padding must stay as they are
+        let palette = [[0x00, 0x00, 0x00, 7], [0x10, 0x20, 0x30, 7]];
+        let mut bmp = bmp(3, 1, 1, BI_RGB, &palette, &[0b1010_0000, 0, 0, 0]);
+        invert(&mut bmp).unwrap();
+
+        assert_eq!(bmp[54..58], [0xFF, 0xFF, 0xFF, 7]);
+        assert_eq!(bmp[58..62], [0xEF, 0xDF, 0xCF, 7]);
+        assert_eq!(bmp[62..], [0b1010_0000, 0, 0, 0]);
+    }
+
+    #[test]
+    fn invert_keeps_bits_outside_the_channel_masks() {
+        // Rgb555, the top bit isn't part of any channel
+        let masks = [0x7C00, 0x03E0, 0x001F, 0];
+        let mut bmp = bmp_with_masks(2, 1, 16, masks, &[0x00, 0x80, 0x34, 0x12]);
+        invert(&mut bmp).unwrap();
+        assert_eq!(bmp[70..], [0xFF, 0xFF, 0xCB, 0x6D]);
+
+        // Rgb888 in 32 bits, the top byte isn't part of any channel
+        let masks = [0xFF0000, 0x00FF00, 0x0000FF, 0];
+        let mut bmp = bmp_with_masks(1, 1, 32, masks, &[1, 2, 3, 0xAA]);
+        invert(&mut bmp).unwrap();
+        assert_eq!(bmp[70..], [0xFE, 0xFD, 0xFC, 0xAA]);
+    }
+
+    #[test]
+    fn invert_implied_masks_like_explicit_ones() {
+        let pixels = [1, 2, 3, 0xAA, 4, 5, 6, 0xBB];
+
+        let mut implied = bmp(2, 1, 32, BI_RGB, &[], &pixels);
+        invert(&mut implied).unwrap();
+
+        let masks = [0xFF0000, 0x00FF00, 0x0000FF, 0];
+        let mut explicit = bmp_with_masks(2, 1, 32, masks, &pixels);
+        invert(&mut explicit).unwrap();
+
+        assert_eq!(implied[54..], explicit[70..]);
+        assert_eq!(
+            implied[54..],
+            [0xFE, 0xFD, 0xFC, 0xAA, 0xFB, 0xFA, 0xF9, 0xBB]
+        );
+
+        // Rgb555 keeps the unused top bit
+        let mut bmp = bmp(2, 1, 16, BI_RGB, &[], &[0x00, 0x80, 0x34, 0x12]);
+        invert(&mut bmp).unwrap();
+        assert_eq!(bmp[54..], [0xFF, 0xFF, 0xCB, 0x6D]);
+    }
+}
@@ -0,0 +1,54 @@
+use memmap2::{MmapMut, MmapOptions};
+use std::{
+    fs::File,
+    io::{self, Read},
+    ops::{Deref, DerefMut},
+    path::Path,
+};
+
+/// The bytes of an input image, either read into memory or mapped from the file
+pub enum Image {
+    Read(Vec<u8>),
+    Mapped(MmapMut),
+}
+
+impl Image {
+    /// Read the whole file into memory
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut image = Vec::new();
+        File::open(path)?.read_to_end(&mut image)?;
+        Ok(Self::Read(image))
+    }
+
+    /// Map the file into memory.
+    ///
+    /// The mapping is copy-on-write, so pages are only loaded once they're touched and
+    /// the processed image that is written back into it never reaches the file.
+    pub fn map(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Safety: The file is opened read-only and the mapping is private.
+        // Other processes modifying the file while we run is undefined behaviour we accept.
+        let mapping = unsafe { MmapOptions::new().map_copy(&file)? };
+        Ok(Self::Mapped(mapping))
+    }
+}
+
+impl Deref for Image {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Image::Read(image) => image,
+            Image::Mapped(mapping) => mapping,
+        }
+    }
+}
+
+impl DerefMut for Image {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Image::Read(image) => image,
+            Image::Mapped(mapping) => mapping,
+        }
+    }
+}
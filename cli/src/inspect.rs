@@ -0,0 +1,118 @@
+use crate::rle::{self, RecordKind, Records, Stats};
+use std::{fs::File, io::Read, path::Path};
+
+/// Print every record of the encoded stream in the file, followed by a summary.
+///
+/// With `encode` the file is first encoded like the host library would before sending it,
+/// and the stream is checked to decode back to the file.
+pub fn run(path: &Path, summary_only: bool, encode: bool) -> anyhow::Result<()> {
+    let mut input = Vec::new();
+    File::open(path)?.read_to_end(&mut input)?;
+
+    let encoded;
+    let stream = if encode {
+        encoded = rle::encode(&input)?;
+        &encoded
+    } else {
+        &input
+    };
+
+    if !summary_only {
+        print_records(stream);
+        println!();
+    }
+
+    print_summary(&Stats::new(stream));
+
+    if encode {
+        print_round_trip(&input, stream);
+    }
+
+    Ok(())
+}
+
+fn print_records(stream: &[u8]) {
+    println!("{:>10}  {:>6}  {:<18}  data", "offset", "header", "record");
+
+    for record in Records::new(stream) {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                println!("{:>#10x}  {:>#6x}  MALFORMED: {e}", e.offset, e.header);
+                continue;
+            }
+        };
+
+        let description = match record.kind {
+            RecordKind::Literal => format!("literal x{}", record.count),
+            RecordKind::Repeat { block_size } => format!("repeat {block_size}B x{}", record.count),
+        };
+
+        println!(
+            "{:>#10x}  {:>#6x}  {description:<18}  {}",
+            record.offset,
+            record.header,
+            hex(record.data)
+        );
+    }
+}
+
+fn print_summary(stats: &Stats) {
+    println!("Encoded bytes: {}", stats.encoded_bytes);
+    println!("Decoded bytes: {}", stats.decoded_bytes);
+    if let Some(ratio) = stats.ratio() {
+        println!("Compression ratio: {ratio:.3}");
+    }
+    println!(
+        "Literal records: {} ({} decoded bytes)",
+        stats.literal_records, stats.literal_bytes
+    );
+    for (i, (records, bytes)) in stats
+        .repeat_records
+        .iter()
+        .zip(stats.repeat_bytes)
+        .enumerate()
+    {
+        println!(
+            "Repeat records with block size {}: {records} ({bytes} decoded bytes)",
+            i + 1
+        );
+    }
+
+    println!("Repeat count histogram:");
+    for (i, records) in stats.repeat_count_histogram.iter().enumerate() {
+        if *records > 0 {
+            println!("  x{:<2} {records}", i + 1);
+        }
+    }
+
+    if stats.truncated {
+        println!("The stream is truncated, the last record is malformed");
+    }
+}
+
+fn print_round_trip(input: &[u8], stream: &[u8]) {
+    // The stream was just encoded, so it can't be malformed
+    let decoded = rle::decode(stream).unwrap_or_default();
+
+    match input.iter().zip(&decoded).position(|(a, b)| a != b) {
+        None if input.len() == decoded.len() => {
+            println!("Round trip: the stream decodes to the input")
+        }
+        Some(offset) => {
+            println!("Round trip: the decoded stream differs from the input at offset {offset:#x}")
+        }
+        None => println!(
+            "Round trip: the stream decodes to {} bytes instead of {}",
+            decoded.len(),
+            input.len()
+        ),
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
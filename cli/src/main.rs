@@ -1,96 +1,137 @@
-use clap::Parser;
-use std::{
-    error::Error,
-    ffi::c_int,
-    fmt::Display,
-    fs::File,
-    io::{Read, Write},
-    path::PathBuf,
-    ptr::null_mut,
-};
+use acceleratorinator_sys::safe::{Connection, RetryPolicy};
+use clap::{Args, Parser, Subcommand};
+use image::Image;
+use std::{fs::File, io::Write, path::PathBuf, time::Duration};
+use timings::{Timings, TimingsFormat};
+
+mod bmp;
+mod image;
+mod inspect;
+mod rle;
+mod timings;
+mod verify;
+
 #[derive(Parser)]
-struct Args {
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    process: Option<ProcessArgs>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Send a bmp file to the acceleratorinator and store the result (the default)
+    Process(ProcessArgs),
+    /// Decode and annotate every record of an RLE encoded stream
+    Inspect {
+        /// The path to the encoded stream, e.g. a captured bulk payload, or to the input with `--encode`
+        path: PathBuf,
+        /// Only print the statistics, not every record
+        #[arg(long)]
+        summary: bool,
+        /// Treat the file as unencoded input, e.g. a bmp file, and encode it like the host library does
+        #[arg(long)]
+        encode: bool,
+    },
+    /// Send a bmp file to the acceleratorinator and check the result against a locally inverted copy
+    Verify {
+        /// The path to the bmp file on disk
+        bmp_path: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct ProcessArgs {
     /// The path to the bmp file on disk
     bmp_path: PathBuf,
     /// The path where the returned bmp file is stored
     output_path: PathBuf,
+    /// Print how long every phase took, in human readable or JSON form
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "human"
+    )]
+    timings: Option<TimingsFormat>,
+    /// How many times to reconnect and resend the image after a USB error.
+    ///
+    /// The device isn't reset, so a resend after an error during the upload can fail to parse or return a garbage image.
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+    /// Memory-map the bmp file instead of reading it into memory first
+    #[arg(long)]
+    mmap: bool,
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let cli = Cli::parse();
+
+    match (cli.command, cli.process) {
+        (Some(Command::Process(args)), _) | (None, Some(args)) => process(args),
+        (
+            Some(Command::Inspect {
+                path,
+                summary,
+                encode,
+            }),
+            _,
+        ) => inspect::run(&path, summary, encode),
+        (Some(Command::Verify { bmp_path }), _) => verify::run(&bmp_path),
+        (None, None) => unreachable!("clap requires the process args when no command is given"),
+    }
+}
 
+fn process(args: ProcessArgs) -> anyhow::Result<()> {
     // Easier setup for debugging:
-    // let args = Args {
+    // let args = ProcessArgs {
     //     bmp_path: "../Bird-inverted.bmp".into(),
     //     output_path: "../output.bmp".into(),
+    //     timings: None,
+    //     retries: 0,
+    //     mmap: false,
     // };
 
-    let mut image = Vec::new();
-    File::open(args.bmp_path)?.read_to_end(&mut image)?;
-
-    let mut connection = null_mut();
-    unsafe {
-        println!("Creating USB");
-        wrap(|| acceleratorinator_sys::cring_usb_create(&mut connection as *mut _))?;
-
-        println!("Connecting USB to acceleratorinator");
-        wrap(|| {
-            acceleratorinator_sys::cring_usb_connect(
-                connection,
-                acceleratorinator_sys::CRING_ACC_VID as u16,
-                acceleratorinator_sys::CRING_ACC_PID as u16,
-            )
-        })?;
-
-        println!("Sending BMP image");
-        wrap(|| {
-            acceleratorinator_sys::cring_acc_send_bmp(
-                connection,
-                image.as_mut_ptr(),
-                image.len(),
-            )
-        })?;
-        
-        File::create(&args.output_path)?.write_all(&image)?;
-
-        println!("Done. Freeing USB");
-        wrap(|| acceleratorinator_sys::cring_usb_free(&mut connection as *mut _))?;
-    }
-
-    Ok(())
-}
-
-fn wrap(f: impl FnOnce() -> c_int) -> Result<u32, CringError> {
-    let val = f();
-
-    if val >= 0 {
-        Ok(val as u32)
-    } else {
-        Err(CringError(val))
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct CringError(c_int);
-
-impl Display for CringError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Cring error `{}` => ", self.0)?;
+    let mut timings = Timings::default();
 
-        match self.0 {
-            acceleratorinator_sys::CRING_EALREADY => write!(f, "ALREADY")?,
-            acceleratorinator_sys::CRING_EINVAL => write!(f, "INVAL")?,
-            acceleratorinator_sys::CRING_ENOTPRESENT => write!(f, "NOTPRESENT")?,
-            acceleratorinator_sys::CRING_EUSB => write!(f, "USB")?,
-
-            acceleratorinator_sys::CRING_EACC_UNKNOWN => write!(f, "ACC_UNKNOWN")?,
-            acceleratorinator_sys::CRING_EACC_UNSUP_COMP => write!(f, "ACC_UNSUP_COMP")?,
-            acceleratorinator_sys::CRING_EACC_PARSE => write!(f, "ACC_PARSE")?,
-            _ => unimplemented!(),
+    let mut image = timings.measure("read input", || {
+        if args.mmap {
+            Image::map(&args.bmp_path)
+        } else {
+            Image::read(&args.bmp_path)
         }
-
-        Ok(())
+    })?;
+    timings.set_image_len(image.len());
+
+    bmp::validate(&image)?;
+    // The host library aborts the whole process on images its encoder panics on
+    rle::encode(&image)?;
+
+    eprintln!("Connecting USB to acceleratorinator");
+    let mut connection = timings.measure("connect", Connection::connect_acceleratorinator)?;
+
+    eprintln!("Sending BMP image");
+    let retry_policy = RetryPolicy {
+        max_attempts: args.retries.saturating_add(1),
+        backoff: Duration::from_millis(100),
+    };
+    timings.measure(timings::ROUND_TRIP, || {
+        connection.send_bmp_with_retry(&mut image, &retry_policy)
+    })?;
+
+    timings.measure("write output", || {
+        File::create(&args.output_path)?.write_all(&image)
+    })?;
+
+    eprintln!("Done. Freeing USB");
+    connection.close()?;
+
+    if let Some(format) = args.timings {
+        timings.print(format);
     }
-}
 
-impl Error for CringError {}
+    Ok(())
+}
@@ -0,0 +1,469 @@
+//! Walker for the RLE stream that is exchanged with the acceleratorinator.
+//!
+//! Every record starts with a one byte header. The lower two bits hold the block size and the
+//! upper six bits hold the count minus one.
+//!
+//! - Block size `0` is a literal record: the header is followed by `count` bytes that are copied as-is.
+//! - Block size `1..=3` is a repeat record: the header is followed by one block of `block size` bytes
+//!   that is repeated `count` times.
+
+use std::{error::Error, fmt::Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Literal,
+    Repeat { block_size: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    /// Offset of the header in the encoded stream
+    pub offset: usize,
+    pub header: u8,
+    pub kind: RecordKind,
+    /// The amount of literal bytes or the amount of times the block is repeated
+    pub count: u8,
+    /// The literal bytes or the repeated block
+    pub data: &'a [u8],
+}
+
+impl Record<'_> {
+    /// The amount of bytes this record produces when decoded
+    pub fn decoded_len(&self) -> usize {
+        match self.kind {
+            RecordKind::Literal => self.data.len(),
+            RecordKind::Repeat { block_size } => block_size as usize * self.count as usize,
+        }
+    }
+}
+
+/// A record whose header promises more bytes than are left in the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Malformed {
+    pub offset: usize,
+    pub header: u8,
+    pub needed: usize,
+    pub available: usize,
+}
+
+impl Display for Malformed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "header {:#04x} at offset {:#x} needs {} data bytes, but only {} are left",
+            self.header, self.offset, self.needed, self.available
+        )
+    }
+}
+
+/// Iterator over the records of an encoded stream.
+///
+/// Stops after the first malformed record since the rest of the stream can't be interpreted anymore.
+pub struct Records<'a> {
+    stream: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Records<'a> {
+    pub fn new(stream: &'a [u8]) -> Self {
+        Self { stream, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, Malformed>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        let (&header, rest) = self.stream.get(offset..)?.split_first()?;
+
+        let count = (header >> 2) + 1;
+        let (kind, needed) = match header & 0b11 {
+            0 => (RecordKind::Literal, count as usize),
+            block_size => (RecordKind::Repeat { block_size }, block_size as usize),
+        };
+
+        let Some(data) = rest.get(..needed) else {
+            self.offset = self.stream.len();
+            return Some(Err(Malformed {
+                offset,
+                header,
+                needed,
+                available: rest.len(),
+            }));
+        };
+
+        self.offset += 1 + needed;
+
+        Some(Ok(Record {
+            offset,
+            header,
+            kind,
+            count,
+            data,
+        }))
+    }
+}
+
+/// Statistics about the records of an encoded stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub encoded_bytes: usize,
+    pub decoded_bytes: usize,
+    pub literal_records: usize,
+    pub literal_bytes: usize,
+    /// Indexed by block size - 1
+    pub repeat_records: [usize; 3],
+    /// Indexed by block size - 1
+    pub repeat_bytes: [usize; 3],
+    /// The amount of repeat records per repeat count, indexed by count - 1
+    pub repeat_count_histogram: [usize; 64],
+    /// Set when the stream ends in a malformed record
+    pub truncated: bool,
+}
+
+impl Stats {
+    pub fn new(stream: &[u8]) -> Self {
+        let mut stats = Self {
+            encoded_bytes: stream.len(),
+            decoded_bytes: 0,
+            literal_records: 0,
+            literal_bytes: 0,
+            repeat_records: [0; 3],
+            repeat_bytes: [0; 3],
+            repeat_count_histogram: [0; 64],
+            truncated: false,
+        };
+
+        for record in Records::new(stream) {
+            match record {
+                Ok(record) => stats.add(&record),
+                Err(_) => stats.truncated = true,
+            }
+        }
+
+        stats
+    }
+
+    fn add(&mut self, record: &Record) {
+        self.decoded_bytes += record.decoded_len();
+
+        match record.kind {
+            RecordKind::Literal => {
+                self.literal_records += 1;
+                self.literal_bytes += record.decoded_len();
+            }
+            RecordKind::Repeat { block_size } => {
+                self.repeat_records[block_size as usize - 1] += 1;
+                self.repeat_bytes[block_size as usize - 1] += record.decoded_len();
+                self.repeat_count_histogram[record.count as usize - 1] += 1;
+            }
+        }
+    }
+
+    /// The decoded size divided by the encoded size, or `None` for an empty stream
+    pub fn ratio(&self) -> Option<f64> {
+        if self.encoded_bytes == 0 {
+            return None;
+        }
+
+        Some(self.decoded_bytes as f64 / self.encoded_bytes as f64)
+    }
+}
+
+/// Decode the whole stream
+pub fn decode(stream: &[u8]) -> Result<Vec<u8>, Malformed> {
+    let mut decoded = Vec::new();
+
+    for record in Records::new(stream) {
+        let record = record?;
+        match record.kind {
+            RecordKind::Literal => decoded.extend_from_slice(record.data),
+            RecordKind::Repeat { .. } => {
+                (0..record.count).for_each(|_| decoded.extend_from_slice(record.data))
+            }
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// The longest literal record the encoder emits, even though the header could describe 64 bytes
+const MAX_LITERAL_LEN: usize = 32;
+
+/// A run the host library's encoder panics on (a division by zero), so it can't be encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodePanic {
+    /// Offset of the run in the input
+    pub offset: usize,
+    /// The length of the run in bytes, a multiple of 256
+    pub run_len: usize,
+}
+
+impl Display for EncodePanic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the host library's encoder panics on the run of {} bytes at input offset {:#x}",
+            self.run_len, self.offset
+        )
+    }
+}
+
+impl Error for EncodePanic {}
+
+/// Encode the input the same way `cring_rle_encode` in the host library does, quirks included.
+///
+/// At every position the encoder picks whichever is cheapest per input byte: a literal record of up
+/// to [MAX_LITERAL_LEN] bytes, or a repeat record of the longest run of 1, 2 or 3 byte blocks.
+/// It doesn't look ahead, so a run that starts inside a literal is swallowed by it.
+/// The run length isn't capped though. The count in the header wraps every 64 repeats while the whole
+/// run is skipped, so longer runs decode shorter than the input. Runs that are a multiple of 256 bytes
+/// make the library panic, which is returned as an error here.
+pub fn encode(input: &[u8]) -> Result<Vec<u8>, EncodePanic> {
+    let mut stream = Vec::new();
+    let mut offset = 0;
+
+    while offset < input.len() {
+        let rest = &input[offset..];
+
+        let literal_len = rest.len().min(MAX_LITERAL_LEN);
+        let mut best_cost = cost(literal_len + 1, literal_len).unwrap();
+        let mut best_block_size = 0;
+
+        for block_size in 1..=3 {
+            let cost = match block_repeats(rest, block_size) {
+                Some(repeats) => cost(block_size + 1, repeats * block_size).ok_or(EncodePanic {
+                    offset,
+                    run_len: repeats * block_size,
+                })?,
+                None => u16::MAX,
+            };
+
+            // Ties go to the earlier option
+            if cost < best_cost {
+                best_cost = cost;
+                best_block_size = block_size;
+            }
+        }
+
+        if best_block_size == 0 {
+            stream.push(header(literal_len, 0));
+            stream.extend_from_slice(&rest[..literal_len]);
+            offset += literal_len;
+        } else {
+            let repeats = block_repeats(rest, best_block_size).unwrap();
+            stream.push(header(repeats, best_block_size));
+            stream.extend_from_slice(&rest[..best_block_size]);
+            offset += repeats * best_block_size;
+        }
+    }
+
+    Ok(stream)
+}
+
+/// The amount of times the first block of `block_size` bytes is repeated at the start of the input,
+/// or `None` if the input is shorter than one block
+fn block_repeats(input: &[u8], block_size: usize) -> Option<usize> {
+    let block = input.get(..block_size)?;
+
+    Some(
+        input
+            .chunks_exact(block_size)
+            .take_while(|chunk| chunk == &block)
+            .count(),
+    )
+}
+
+/// Encoded bytes per 1000 input bytes, with the same `u8` truncation as the library.
+/// `None` where the library divides by zero.
+fn cost(encoded_len: usize, input_len: usize) -> Option<u16> {
+    (encoded_len as u8 as u16 * 1000).checked_div(input_len as u8 as u16)
+}
+
+/// The header of a record, with the count wrapping like in the library
+fn header(count: usize, block_size: usize) -> u8 {
+    ((count as u8).wrapping_sub(1) << 2) | block_size as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_record() {
+        // Count 3, block size 0
+        let stream = [0x08, 1, 2, 3];
+        let records = Records::new(&stream).collect::<Vec<_>>();
+
+        assert_eq!(
+            records,
+            [Ok(Record {
+                offset: 0,
+                header: 0x08,
+                kind: RecordKind::Literal,
+                count: 3,
+                data: &[1, 2, 3],
+            })]
+        );
+        assert_eq!(records[0].unwrap().decoded_len(), 3);
+    }
+
+    #[test]
+    fn repeat_records() {
+        // Count 4 of a 1 byte block, then count 64 of a 3 byte block
+        let stream = [0x0D, 0xAA, 0xFF, 1, 2, 3];
+        let records = Records::new(&stream)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        assert_eq!(records.len(), 2);
+
+        assert_eq!(records[0].offset, 0);
+        assert_eq!(records[0].kind, RecordKind::Repeat { block_size: 1 });
+        assert_eq!(records[0].count, 4);
+        assert_eq!(records[0].data, [0xAA]);
+        assert_eq!(records[0].decoded_len(), 4);
+
+        assert_eq!(records[1].offset, 2);
+        assert_eq!(records[1].kind, RecordKind::Repeat { block_size: 3 });
+        assert_eq!(records[1].count, 64);
+        assert_eq!(records[1].data, [1, 2, 3]);
+        assert_eq!(records[1].decoded_len(), 192);
+    }
+
+    #[test]
+    fn lone_zero_header_is_malformed() {
+        // A literal of 1 byte without the byte
+        let stream = [0x00];
+
+        assert_eq!(
+            Records::new(&stream).collect::<Vec<_>>(),
+            [Err(Malformed {
+                offset: 0,
+                header: 0x00,
+                needed: 1,
+                available: 0,
+            })]
+        );
+    }
+
+    #[test]
+    fn stops_after_malformed_record() {
+        // A valid repeat, then a 2 byte block repeat with only 1 byte left
+        let stream = [0x01, 0xAA, 0x02, 0xBB];
+        let mut records = Records::new(&stream);
+
+        assert!(records.next().unwrap().is_ok());
+        assert_eq!(
+            records.next(),
+            Some(Err(Malformed {
+                offset: 2,
+                header: 0x02,
+                needed: 2,
+                available: 1,
+            }))
+        );
+        assert_eq!(records.next(), None);
+    }
+
+    #[test]
+    fn empty_stream() {
+        assert_eq!(Records::new(&[]).next(), None);
+    }
+
+    #[test]
+    fn stats() {
+        // Literal x2, repeat 1B x4, repeat 2B x64, repeat 1B x1
+        let stream = [0x04, 1, 2, 0x0D, 0xAA, 0xFE, 3, 4, 0x01, 0xBB];
+        let stats = Stats::new(&stream);
+
+        assert_eq!(stats.encoded_bytes, 10);
+        assert_eq!(stats.decoded_bytes, 2 + 4 + 128 + 1);
+        assert_eq!(stats.literal_records, 1);
+        assert_eq!(stats.literal_bytes, 2);
+        assert_eq!(stats.repeat_records, [2, 1, 0]);
+        assert_eq!(stats.repeat_bytes, [5, 128, 0]);
+        assert_eq!(stats.repeat_count_histogram[0], 1);
+        assert_eq!(stats.repeat_count_histogram[3], 1);
+        assert_eq!(stats.repeat_count_histogram[63], 1);
+        assert_eq!(stats.repeat_count_histogram.iter().sum::<usize>(), 3);
+        assert!(!stats.truncated);
+        assert_eq!(stats.ratio(), Some(13.5));
+    }
+
+    #[test]
+    fn stats_of_truncated_stream() {
+        let stats = Stats::new(&[0x01, 0xAA, 0x02, 0xBB]);
+
+        assert_eq!(stats.decoded_bytes, 1);
+        assert!(stats.truncated);
+    }
+
+    #[test]
+    fn stats_of_empty_stream() {
+        assert_eq!(Stats::new(&[]).ratio(), None);
+    }
+
+    #[test]
+    fn encode_literals_and_repeats() {
+        // The last 3 bytes cost the same as a literal or a 3 byte block, and the literal wins the tie
+        let input = [9, 9, 9, 9, 9, 5, 6, 5, 6, 5, 6, 1, 2, 3];
+
+        assert_eq!(encode(&input), Ok(vec![0x11, 9, 0x0A, 5, 6, 0x08, 1, 2, 3]));
+    }
+
+    #[test]
+    fn encode_only_repeats_at_the_start_of_a_literal() {
+        // The run is swallowed by the literal, because the literal is cheaper at offset 0
+        let input = [1, 9, 9, 9, 9, 9];
+
+        assert_eq!(encode(&input), Ok(vec![0x14, 1, 9, 9, 9, 9, 9]));
+    }
+
+    #[test]
+    fn encode_splits_literals_at_32_bytes() {
+        let input = (0..40).collect::<Vec<u8>>();
+        let stream = encode(&input).unwrap();
+
+        assert_eq!(stream[0], 0x7C);
+        assert_eq!(stream[33], 0x1C);
+        assert_eq!(stream.len(), 2 + 40);
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        let input = b"BM\0\0\0\0\xff\xff\xff\xff\xff\xff\x12\x34\x56\x12\x34\x56\x12\x34\x56hello";
+        let stream = encode(input).unwrap();
+
+        assert_eq!(decode(&stream).unwrap(), input);
+    }
+
+    #[test]
+    fn encode_of_empty_input() {
+        assert_eq!(encode(&[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn encode_wraps_runs_longer_than_64() {
+        // The library skips all 65 bytes, but the header count wraps to 1
+        let stream = encode(&[0; 65]).unwrap();
+
+        assert_eq!(stream, [0x01, 0]);
+        assert_eq!(decode(&stream).unwrap(), [0]);
+    }
+
+    #[test]
+    fn encode_panics_like_the_library() {
+        let mut input = vec![0; 256];
+        input.extend_from_slice(&[1, 2, 3]);
+
+        assert_eq!(
+            encode(&input),
+            Err(EncodePanic {
+                offset: 0,
+                run_len: 256,
+            })
+        );
+    }
+}
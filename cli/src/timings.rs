@@ -0,0 +1,111 @@
+use clap::ValueEnum;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TimingsFormat {
+    Human,
+    Json,
+}
+
+/// Wall clock time spent in every phase of processing an image
+#[derive(Debug, Default)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+    image_len: usize,
+}
+
+impl Timings {
+    /// Run `f` and record how long it took under the given phase name
+    pub fn measure<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.phases.push((phase, start.elapsed()));
+        result
+    }
+
+    /// Set the size of the image, used to calculate the throughput of the round trip
+    pub fn set_image_len(&mut self, image_len: usize) {
+        self.image_len = image_len;
+    }
+
+    fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, duration)| *duration).sum()
+    }
+
+    /// The image bytes per second of the round trip phase, if it took a measurable amount of time
+    fn throughput(&self) -> Option<f64> {
+        let (_, duration) = self.phases.iter().find(|(phase, _)| *phase == ROUND_TRIP)?;
+
+        if duration.is_zero() {
+            return None;
+        }
+
+        Some(self.image_len as f64 / duration.as_secs_f64())
+    }
+
+    pub fn print(&self, format: TimingsFormat) {
+        match format {
+            TimingsFormat::Human => {
+                println!("Timings:");
+                for (phase, duration) in &self.phases {
+                    println!("  {phase:<14} {:>10.3} ms", millis(*duration));
+                }
+                println!("  {:<14} {:>10.3} ms", "total", millis(self.total()));
+                if let Some(throughput) = self.throughput() {
+                    println!("  throughput     {:>10.1} KiB/s", throughput / 1024.0);
+                }
+            }
+            TimingsFormat::Json => {
+                let phases = self
+                    .phases
+                    .iter()
+                    .map(|(phase, duration)| format!("\"{phase}\":{:.3}", millis(*duration)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                print!(
+                    "{{\"phases_ms\":{{{phases}}},\"total_ms\":{:.3},\"image_bytes\":{}",
+                    millis(self.total()),
+                    self.image_len
+                );
+                if let Some(throughput) = self.throughput() {
+                    print!(",\"throughput_bytes_per_s\":{throughput:.1}");
+                }
+                println!("}}");
+            }
+        }
+    }
+}
+
+/// The phase covering the library call that sends the image and receives the result.
+///
+/// This includes the host encode, upload, device processing, download and host decode.
+/// The library doesn't report these separately.
+pub const ROUND_TRIP: &str = "round trip";
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throughput() {
+        let mut timings = Timings {
+            phases: vec![
+                ("read input", Duration::from_secs(5)),
+                (ROUND_TRIP, Duration::from_secs(2)),
+            ],
+            image_len: 1000,
+        };
+        assert_eq!(timings.throughput(), Some(500.0));
+
+        timings.phases[1].1 = Duration::ZERO;
+        assert_eq!(timings.throughput(), None);
+
+        timings.phases.pop();
+        assert_eq!(timings.throughput(), None);
+    }
+}
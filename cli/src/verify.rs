@@ -0,0 +1,70 @@
+use crate::{bmp, rle};
+use acceleratorinator_sys::safe::Connection;
+use std::{fs::File, io::Read, path::Path};
+
+/// Where the device output first differs from the expected output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    pub offset: usize,
+    pub expected: u8,
+    pub actual: u8,
+    /// The amount of mismatching bytes in total
+    pub count: usize,
+}
+
+/// Send the bmp image to the acceleratorinator and compare the result with a locally inverted copy.
+///
+/// Returns the first mismatch, or `None` if the device output is as expected.
+pub fn verify(connection: &mut Connection, bmp: &[u8]) -> anyhow::Result<Option<Mismatch>> {
+    let mut expected = bmp.to_vec();
+    bmp::invert(&mut expected)?;
+
+    let mut actual = bmp.to_vec();
+    connection.send_bmp(&mut actual)?;
+
+    let mut mismatches = expected
+        .iter()
+        .zip(&actual)
+        .enumerate()
+        .filter(|(_, (expected, actual))| expected != actual);
+
+    Ok(mismatches
+        .next()
+        .map(|(offset, (&expected, &actual))| Mismatch {
+            offset,
+            expected,
+            actual,
+            count: mismatches.count() + 1,
+        }))
+}
+
+/// Send the bmp file to the acceleratorinator and report whether the result matches a locally inverted copy
+pub fn run(bmp_path: &Path) -> anyhow::Result<()> {
+    let mut image = Vec::new();
+    File::open(bmp_path)?.read_to_end(&mut image)?;
+
+    // Checked before connecting, so an invalid file doesn't need a device
+    bmp::validate(&image)?;
+    // The host library aborts the whole process on images its encoder panics on
+    rle::encode(&image)?;
+
+    eprintln!("Connecting USB to acceleratorinator");
+    let mut connection = Connection::connect_acceleratorinator()?;
+
+    eprintln!("Sending BMP image");
+    let mismatch = verify(&mut connection, &image)?;
+    connection.close()?;
+
+    let Some(mismatch) = mismatch else {
+        println!("The device output matches the expected output");
+        return Ok(());
+    };
+
+    println!(
+        "First mismatch at offset {:#x}: expected {:#04x}, got {:#04x}",
+        mismatch.offset, mismatch.expected, mismatch.actual
+    );
+    println!("Mismatching bytes in total: {}", mismatch.count);
+
+    anyhow::bail!("The device output doesn't match the expected output")
+}